@@ -0,0 +1,159 @@
+use crate::BloomFilter;
+use std::convert::TryInto;
+
+// Identifies a serialized BloomCascade, mirroring BloomFilter's own header.
+const MAGIC: &[u8; 4] = b"BLMC";
+
+// A pathological input (the same element present in both `include` and
+// `exclude`) can never be resolved by the cascade, since no Bloom filter
+// can both contain and exclude the same byte string. Capping the number
+// of levels keeps `build` from looping forever over such inputs instead
+// of silently hanging; the caller is expected to pass disjoint sets.
+const MAX_LEVELS: usize = 64;
+
+/// A multi-level Bloom filter cascade (as used by CRLite / rust_cascade)
+/// that gives zero false positives for two known, disjoint sets of
+/// elements while staying far more compact than a perfect hash set would
+/// be. Level 0 is a `BloomFilter` over the `include` set; any `exclude`
+/// element that falsely matches level 0 is captured in a level-1 filter;
+/// any `include` element that falsely matches level 1 is captured in a
+/// level-2 filter, and so on, alternating which set is being captured
+/// until a level produces no false positives.
+pub struct BloomCascade {
+    levels: Vec<BloomFilter>,
+}
+
+impl BloomCascade {
+    /// Builds a cascade that gives exact answers for every element of
+    /// `include` and `exclude`. `include` and `exclude` must be disjoint;
+    /// an element present in both can never be resolved.
+    pub fn build(include: &[&[u8]], exclude: &[&[u8]], error_rate: f64) -> BloomCascade {
+        let include: Vec<Vec<u8>> = include.iter().map(|item| item.to_vec()).collect();
+        let exclude: Vec<Vec<u8>> = exclude.iter().map(|item| item.to_vec()).collect();
+
+        let mut levels = Vec::new();
+        let mut target = include.clone();
+        let mut level_index = 0;
+        loop {
+            let complement = if level_index % 2 == 0 { &exclude } else { &include };
+            let capacity = target.len().max(1);
+            let mut filter = BloomFilter::new(capacity, error_rate, false);
+            for item in &target {
+                filter.add(item).unwrap();
+            }
+            let false_positives: Vec<Vec<u8>> = complement
+                .iter()
+                .filter(|item| filter.contains(item))
+                .cloned()
+                .collect();
+            levels.push(filter);
+            if false_positives.is_empty() || level_index + 1 >= MAX_LEVELS {
+                break;
+            }
+            target = false_positives;
+            level_index += 1;
+        }
+        BloomCascade { levels }
+    }
+
+    /// Returns how many levels the cascade ended up with.
+    pub fn depth(&self) -> usize {
+        self.levels.len()
+    }
+
+    /// Resolves membership by walking the levels top-down. An element
+    /// absent at an even-indexed level was never in `include`; absent at
+    /// an odd-indexed level was captured correcting for a level-0 false
+    /// positive and so is genuinely in `include`. Matching every level
+    /// resolves the same way, one parity past the last level.
+    pub fn contains(&self, data: &[u8]) -> bool {
+        for (i, level) in self.levels.iter().enumerate() {
+            if !level.contains(data) {
+                return i % 2 == 1;
+            }
+        }
+        self.levels.len() % 2 == 1
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        out.extend_from_slice(MAGIC);
+        out.extend_from_slice(&(self.levels.len() as u64).to_le_bytes());
+        for level in &self.levels {
+            let level_bytes = level.to_bytes();
+            out.extend_from_slice(&(level_bytes.len() as u64).to_le_bytes());
+            out.extend_from_slice(&level_bytes);
+        }
+        out
+    }
+
+    pub fn from_bytes(data: &[u8]) -> Result<BloomCascade, &'static str> {
+        if data.len() < 12 {
+            return Err("buffer is too small to contain a BloomCascade header");
+        }
+        if &data[0..4] != MAGIC {
+            return Err("buffer does not start with the BloomCascade magic bytes");
+        }
+        let read_u64 = |data: &[u8], offset: usize| -> Result<u64, &'static str> {
+            let bytes: [u8; 8] = data
+                .get(offset..offset + 8)
+                .ok_or("buffer ends in the middle of a length field")?
+                .try_into()
+                .unwrap();
+            Ok(u64::from_le_bytes(bytes))
+        };
+
+        let num_levels = read_u64(data, 4)? as usize;
+        let mut offset = 12;
+        let mut levels = Vec::with_capacity(num_levels);
+        for _ in 0..num_levels {
+            let level_len = read_u64(data, offset)? as usize;
+            offset += 8;
+            let level_bytes = data
+                .get(offset..offset + level_len)
+                .ok_or("buffer ends in the middle of a level")?;
+            levels.push(BloomFilter::from_bytes(level_bytes)?);
+            offset += level_len;
+        }
+        Ok(BloomCascade { levels })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::BloomCascade;
+
+    #[test]
+    fn test_cascade_resolves_include_and_exclude() {
+        let include: Vec<&[u8]> = vec!["Alice".as_bytes(), "Bob".as_bytes(), "Carol".as_bytes()];
+        let exclude: Vec<&[u8]> = vec!["Dave".as_bytes(), "Eve".as_bytes(), "Frank".as_bytes()];
+        let cascade = BloomCascade::build(&include, &exclude, 0.01);
+        for item in &include {
+            assert!(cascade.contains(item));
+        }
+        for item in &exclude {
+            assert!(!cascade.contains(item));
+        }
+    }
+
+    #[test]
+    fn test_cascade_to_bytes_from_bytes_roundtrip() {
+        let include: Vec<&[u8]> = vec!["Alice".as_bytes(), "Bob".as_bytes()];
+        let exclude: Vec<&[u8]> = vec!["Dave".as_bytes(), "Eve".as_bytes()];
+        let cascade = BloomCascade::build(&include, &exclude, 0.01);
+        let bytes = cascade.to_bytes();
+        let restored = BloomCascade::from_bytes(&bytes).unwrap();
+        assert_eq!(restored.depth(), cascade.depth());
+        for item in &include {
+            assert!(restored.contains(item));
+        }
+        for item in &exclude {
+            assert!(!restored.contains(item));
+        }
+    }
+
+    #[test]
+    fn test_cascade_from_bytes_rejects_bad_magic() {
+        assert!(BloomCascade::from_bytes(&[0; 16]).is_err());
+    }
+}
@@ -0,0 +1,168 @@
+use crate::{hash_indices, iterations, pow2_nbits};
+
+// Counter is the storage type backing each slot. u8 saturates at 255
+// "hits", which is plenty for the threshold-crossing checks this filter
+// is meant for; switch to u16 here if a single bucket can legitimately
+// see more than 255 insertions.
+type Counter = u8;
+
+/// A Bloom filter variant that keeps a small counter per hash position
+/// instead of a single bit, so that, unlike `BloomFilter`, elements can
+/// be removed again with `remove`.
+pub struct CountingBloomFilter {
+    capacity: usize,
+    counters: Vec<Counter>,
+    mask: u64,
+    error_rate: f64,
+    num_of_hashfuncs: usize,
+    num_of_elements: usize,
+    dup_check: bool,
+}
+
+impl CountingBloomFilter {
+    pub fn new(capacity: usize, error_rate: f64, dup_check: bool) -> CountingBloomFilter {
+        if capacity == 0 {
+            panic!("capacity must be greater than zero");
+        }
+        if error_rate <= 0.0 || error_rate > 1.0 {
+            panic!("error_rate must be greater than 0.0 and less than 1.0");
+        }
+        let num_of_bits = pow2_nbits(capacity, error_rate);
+        let num_of_hashfuncs = iterations(num_of_bits, capacity);
+        CountingBloomFilter {
+            counters: vec![0; num_of_bits],
+            mask: (num_of_bits - 1) as u64,
+            capacity,
+            error_rate,
+            num_of_hashfuncs,
+            num_of_elements: 0,
+            dup_check,
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    pub fn len(&self) -> usize {
+        self.num_of_elements
+    }
+
+    pub fn error_rate(&self) -> f64 {
+        self.error_rate
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn indices(&self, data: &[u8]) -> impl Iterator<Item = usize> {
+        hash_indices(data, self.num_of_hashfuncs, self.mask)
+    }
+
+    pub fn add(&mut self, data: &[u8]) -> Result<bool, &'static str> {
+        if self.num_of_elements == self.capacity {
+            return Err("You are adding to the bloom filter that is already full");
+        }
+        let mut exists = true;
+        for index in self.indices(data) {
+            if self.dup_check && self.counters[index] == 0 {
+                exists = false;
+            }
+            self.counters[index] = self.counters[index].saturating_add(1);
+        }
+        if self.dup_check && exists {
+            return Ok(false);
+        }
+        self.num_of_elements += 1;
+        Ok(true)
+    }
+
+    /// Decrements the counters for `data`, undoing a previous `add`. An
+    /// element the filter does not report as present (one that was never
+    /// added, or was removed as often as it was added) is left alone: its
+    /// counters are untouched and `len()` is unchanged, rather than
+    /// desyncing `len()` from the filter's actual membership. A false
+    /// positive cannot be told apart from a real member and is decremented
+    /// like one.
+    pub fn remove(&mut self, data: &[u8]) {
+        if !self.contains(data) {
+            return;
+        }
+        for index in self.indices(data) {
+            self.counters[index] = self.counters[index].saturating_sub(1);
+        }
+        self.num_of_elements = self.num_of_elements.saturating_sub(1);
+    }
+
+    pub fn contains(&self, data: &[u8]) -> bool {
+        self.indices(data).all(|index| self.counters[index] > 0)
+    }
+
+    /// Returns the minimum counter value across all of `data`'s hash
+    /// positions, i.e. an upper bound on how many times it was inserted.
+    /// Useful for checking whether an element has crossed some threshold
+    /// number of insertions.
+    pub fn count_estimate(&self, data: &[u8]) -> usize {
+        self.indices(data)
+            .map(|index| self.counters[index] as usize)
+            .min()
+            .unwrap_or(0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::CountingBloomFilter;
+
+    #[test]
+    fn test_add_and_contains() {
+        let mut b = CountingBloomFilter::new(100, 0.01, true);
+        assert!(b.add("Test".as_bytes()).unwrap());
+        assert!(b.contains("Test".as_bytes()));
+    }
+
+    #[test]
+    fn test_remove() {
+        let mut b = CountingBloomFilter::new(100, 0.01, true);
+        b.add("Test".as_bytes()).unwrap();
+        assert!(b.contains("Test".as_bytes()));
+        b.remove("Test".as_bytes());
+        assert!(!b.contains("Test".as_bytes()));
+    }
+
+    #[test]
+    fn test_count_estimate() {
+        let mut b = CountingBloomFilter::new(100, 0.01, false);
+        assert_eq!(b.count_estimate("Test".as_bytes()), 0);
+        b.add("Test".as_bytes()).unwrap();
+        b.add("Test".as_bytes()).unwrap();
+        b.add("Test".as_bytes()).unwrap();
+        assert_eq!(b.count_estimate("Test".as_bytes()), 3);
+    }
+
+    #[test]
+    fn test_remove_is_saturating() {
+        let mut b = CountingBloomFilter::new(100, 0.01, true);
+        b.remove("Never added".as_bytes());
+        assert!(!b.contains("Never added".as_bytes()));
+    }
+
+    #[test]
+    fn test_remove_of_absent_element_does_not_change_len() {
+        let mut b = CountingBloomFilter::new(100, 0.01, true);
+        b.add("A".as_bytes()).unwrap();
+        b.add("B".as_bytes()).unwrap();
+        assert_eq!(b.len(), 2);
+        b.remove("Never added C".as_bytes());
+        assert_eq!(b.len(), 2);
+        assert!(b.contains("A".as_bytes()));
+        assert!(b.contains("B".as_bytes()));
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_zero_capacity_filter() {
+        let _b = CountingBloomFilter::new(0, 0.01, true);
+    }
+}
@@ -1,31 +1,44 @@
-// mod counting_bloom_filter;
+mod bloom_cascade;
+mod counting_bloom_filter;
 
-extern crate bigint;
 extern crate bit_vec;
 extern crate fasthash;
 
-use bigint::uint::U512;
 use bit_vec::BitVec;
 use fasthash::murmur3::hash128;
-use std::ops::Add;
+use std::collections::HashSet;
+use std::convert::TryInto;
+use std::hash::{Hash, Hasher};
+
+pub use bloom_cascade::BloomCascade;
+pub use counting_bloom_filter::CountingBloomFilter;
+
+// Identifies a serialized BloomFilter and lets from_bytes reject data that
+// isn't ours before it tries to interpret the header fields that follow.
+const MAGIC: &[u8; 4] = b"BLMF";
+const VERSION: u8 = 1;
 
 pub struct BloomFilter {
     capacity: usize,
     bitvec: BitVec,
+    // Bitmask for turning a hash into an index cheaply: bitvec.len() is
+    // always a power of two, so `hash & mask` is equivalent to, and much
+    // cheaper than, `hash % bitvec.len()`.
+    mask: u64,
     error_rate: f64,
     num_of_hashfuncs: usize,
     num_of_elements: usize,
     dup_check: bool,
+    // When `Some`, tracks every bit index `add` has flipped from 0 to 1
+    // since the last `drain_set_indices`. `None` (the default) disables
+    // journalling entirely, so filters that don't need it pay nothing.
+    journal: Option<HashSet<usize>>,
 }
 
-// Counting Bloomfilter gives the opportunity to check certain
-// elements crossed a certain threshold.
-// pub type CountingBloomFilter = BloomFilter;
-
 // The number of bits for the bloom filter is given by the following formula
 // m = math.ceil((n * math.log(p)) / math.log(1.0 / (pow(2.0, math.log(2.0)))))
 #[inline]
-fn nbits(n: usize, p: f64) -> usize {
+pub(crate) fn nbits(n: usize, p: f64) -> usize {
     let numerator = n as f64 * p.ln();
     let denominator = (1.0_f64 / 2.0_f64.powf(2.0_f64.ln())).ln();
     (numerator / denominator).ceil() as usize
@@ -34,10 +47,62 @@ fn nbits(n: usize, p: f64) -> usize {
 // Iterations gives the number of hash functions to be used.
 // The formula is : k = round((m / n) * math.log(2));
 #[inline]
-fn iterations(m: usize, n: usize) -> usize {
+pub(crate) fn iterations(m: usize, n: usize) -> usize {
     ((m as f64 / n as f64) * 2.0_f64.ln()).round() as usize
 }
 
+// The index math below needs the bit storage to be a power of two, so
+// that modulo reduction collapses to a bitwise AND. Rounding the
+// error-rate-derived bit count up to the next power of two costs a
+// little extra memory but avoids any per-hash division or the 512-bit
+// arithmetic this crate used to need to stay unbiased over an arbitrary
+// `m`.
+#[inline]
+pub(crate) fn pow2_nbits(n: usize, p: f64) -> usize {
+    nbits(n, p).next_power_of_two()
+}
+
+// Computes the k bit positions for `data` using Kirsch-Mitzenmacher double
+// hashing: a single 128-bit murmur3 hash is split into two 64-bit halves
+// h1/h2, and the i-th index is `(h1 + i * h2) mod m`, which is statistically
+// indistinguishable from k independent hash functions. Masking instead of
+// the `% m` from the formula is valid because `m` is always a power of two.
+#[inline]
+pub(crate) fn hash_indices(
+    data: &[u8],
+    num_of_hashfuncs: usize,
+    mask: u64,
+) -> impl Iterator<Item = usize> {
+    let hash = hash128(data);
+    let h1 = (hash & (2_u128.pow(64) - 1)) as u64;
+    let h2 = (hash >> 64) as u64;
+    (0..num_of_hashfuncs as u64).map(move |i| (h1.wrapping_add(i.wrapping_mul(h2)) & mask) as usize)
+}
+
+// A `Hasher` that just collects the raw bytes `Hash::hash` writes, rather
+// than reducing them to a 64-bit digest. This lets `add_value`/
+// `contains_value` turn any `Hash` value into the byte slice that the
+// murmur3-backed `add`/`contains` primitives expect, instead of
+// duplicating the hashing logic for a value-oriented API.
+#[derive(Default)]
+struct ByteCollector(Vec<u8>);
+
+impl Hasher for ByteCollector {
+    fn finish(&self) -> u64 {
+        unimplemented!("ByteCollector is only used to capture the bytes Hash writes")
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        self.0.extend_from_slice(bytes);
+    }
+}
+
+fn bytes_of<T: Hash>(value: &T) -> Vec<u8> {
+    let mut collector = ByteCollector::default();
+    value.hash(&mut collector);
+    collector.0
+}
+
 impl BloomFilter {
     pub fn new(capacity: usize, error_rate: f64, dup_check: bool) -> BloomFilter {
         if capacity == 0 {
@@ -46,15 +111,17 @@ impl BloomFilter {
         if error_rate <= 0.0 || error_rate > 1.0 {
             panic!("error_rate must be greater than 0.0 and less than 1.0");
         }
-        let num_of_bits = nbits(capacity, error_rate);
+        let num_of_bits = pow2_nbits(capacity, error_rate);
         let num_of_hashfuncs = iterations(num_of_bits, capacity);
         BloomFilter {
             bitvec: BitVec::from_elem(num_of_bits, false),
+            mask: (num_of_bits - 1) as u64,
             capacity,
             error_rate,
             num_of_hashfuncs,
             num_of_elements: 0,
             dup_check,
+            journal: None,
         }
     }
 
@@ -77,23 +144,42 @@ impl BloomFilter {
         self.len() == 0
     }
 
+    /// Turns on journalling: from now on, `add` records every bit index it
+    /// flips from 0 to 1 so that `drain_set_indices` can return just the
+    /// delta since the last drain, instead of the caller needing to
+    /// rewrite the whole bitmap to persist changes. A no-op if already
+    /// enabled.
+    pub fn enable_journal(&mut self) {
+        self.journal.get_or_insert_with(HashSet::new);
+    }
+
+    /// Returns the bit indices `add` has newly set since the last call to
+    /// `drain_set_indices` (or since `enable_journal`), clearing the
+    /// journal in the process. Returns an empty `Vec` if journalling was
+    /// never enabled.
+    pub fn drain_set_indices(&mut self) -> Vec<usize> {
+        match self.journal.as_mut() {
+            Some(journal) => journal.drain().collect(),
+            None => Vec::new(),
+        }
+    }
+
     pub fn add(&mut self, data: &[u8]) -> Result<bool, &'static str> {
         if self.num_of_elements == self.capacity {
             return Err("You are adding to the bloom filter that is already full");
         }
-        let hash = hash128(data);
-        let hash64_first = (hash & (2_u128.pow(64) - 1)) as u64;
-        let hash64_second = (hash >> 64) as u64;
-        let mut result_hash: U512 = hash64_first.into();
         let mut exists = true;
-        for value in 0..self.num_of_hashfuncs {
-            let temp: U512 = U512::from(value) * U512::from(hash64_second);
-            result_hash = result_hash.add(temp);
-            let index = result_hash % U512::from(self.bitvec_len());
-            if self.dup_check && self.bitvec.get(index.as_u64() as usize) == Some(false) {
+        for index in hash_indices(data, self.num_of_hashfuncs, self.mask) {
+            let was_unset = self.bitvec.get(index) == Some(false);
+            if self.dup_check && was_unset {
                 exists = false;
             }
-            self.bitvec.set(index.as_u64() as usize, true);
+            self.bitvec.set(index, true);
+            if was_unset {
+                if let Some(journal) = self.journal.as_mut() {
+                    journal.insert(index);
+                }
+            }
         }
         if self.dup_check && exists {
             return Ok(false);
@@ -103,19 +189,207 @@ impl BloomFilter {
     }
 
     pub fn contains(&self, data: &[u8]) -> bool {
-        let hash = hash128(data);
-        let hash64_first = (hash & (2_u128.pow(64) - 1)) as u64;
-        let hash64_second = (hash >> 64) as u64;
-        let mut result_hash: U512 = hash64_first.into();
-        for value in 0..self.num_of_hashfuncs {
-            let temp: U512 = U512::from(value) * U512::from(hash64_second);
-            result_hash = result_hash.add(temp);
-            let index = result_hash % U512::from(self.bitvec_len());
-            if self.bitvec.get(index.as_u64() as usize) == Some(false) {
-                return false;
-            }
+        hash_indices(data, self.num_of_hashfuncs, self.mask)
+            .all(|index| self.bitvec.get(index) == Some(true))
+    }
+
+    /// Like `add`, but for any `Hash`-able value instead of a raw byte
+    /// slice. Feeds `value` through its `Hash` impl to collect the bytes
+    /// that make it up, then delegates to `add`, so the result is the
+    /// same murmur3-backed filter, just with a friendlier entry point for
+    /// integers, strings, tuples, or custom `#[derive(Hash)]` types.
+    pub fn add_value<T: Hash>(&mut self, value: &T) -> Result<bool, &'static str> {
+        self.add(&bytes_of(value))
+    }
+
+    /// Like `contains`, but for any `Hash`-able value instead of a raw
+    /// byte slice. See `add_value`.
+    pub fn contains_value<T: Hash>(&self, value: &T) -> bool {
+        self.contains(&bytes_of(value))
+    }
+
+    fn assert_compatible_for_merge(&self, other: &BloomFilter) -> Result<(), &'static str> {
+        if self.bitvec_len() != other.bitvec_len()
+            || self.num_of_hashfuncs != other.num_of_hashfuncs
+            || self.error_rate != other.error_rate
+            || self.capacity != other.capacity
+        {
+            return Err("filters must share capacity, error_rate and num_of_hashfuncs to be combined");
         }
-        true
+        Ok(())
+    }
+
+    /// ORs `other`'s bits into this filter, so this filter ends up
+    /// containing everything either filter contained, e.g. to combine
+    /// independently built shards. Both filters must share `capacity`,
+    /// `error_rate` and `num_of_hashfuncs`. After a union, `len()` is only
+    /// an upper-bound estimate (it can double-count elements the two
+    /// filters had in common) — use `estimated_len` for a principled
+    /// count based on the number of bits actually set. If journalling is
+    /// enabled, every bit this flips from 0 to 1 is recorded just like
+    /// `add` would, so a backing store can still apply only the delta.
+    pub fn union(&mut self, other: &BloomFilter) -> Result<(), &'static str> {
+        self.assert_compatible_for_merge(other)?;
+        if self.journal.is_some() {
+            let newly_set: Vec<usize> = self
+                .bitvec
+                .iter()
+                .zip(other.bitvec.iter())
+                .enumerate()
+                .filter_map(|(index, (self_bit, other_bit))| (!self_bit && other_bit).then_some(index))
+                .collect();
+            self.journal.as_mut().unwrap().extend(newly_set);
+        }
+        self.bitvec.or(&other.bitvec);
+        self.num_of_elements = self.num_of_elements.saturating_add(other.num_of_elements);
+        Ok(())
+    }
+
+    /// ANDs `other`'s bits into this filter, so this filter ends up
+    /// containing only elements that (as far as either filter can tell)
+    /// both filters contained. Both filters must share `capacity`,
+    /// `error_rate` and `num_of_hashfuncs`. Since clearing bits can only
+    /// ever remove elements, never add them, `len()` is lowered to
+    /// `estimated_len()` afterwards (it is never raised) so it (and the
+    /// capacity check in `add`) don't keep reporting the pre-intersect
+    /// count. Unlike
+    /// `union`, this never sets a bit from 0 to 1, so it needs no
+    /// journalling of its own — the journal (which only tracks 0-to-1
+    /// flips) stays accurate without any extra bookkeeping here.
+    pub fn intersect(&mut self, other: &BloomFilter) -> Result<(), &'static str> {
+        self.assert_compatible_for_merge(other)?;
+        self.bitvec.and(&other.bitvec);
+        self.num_of_elements = self.estimated_len().min(self.num_of_elements);
+        Ok(())
+    }
+
+    /// Estimates the number of elements in the filter from the fraction of
+    /// bits that are set, using the standard cardinality formula
+    /// `n ≈ -(m/k) * ln(1 - X/m)` where `X` is the number of set bits, `m`
+    /// is the bit length and `k` is `num_of_hashfuncs`. Unlike `len()`,
+    /// this is accurate even after a `union`, since it doesn't depend on
+    /// bookkeeping that can double-count shared elements.
+    pub fn estimated_len(&self) -> usize {
+        let m = self.bitvec_len() as f64;
+        let k = self.num_of_hashfuncs as f64;
+        let x = self.bitvec.iter().filter(|&bit| bit).count() as f64;
+        if x >= m {
+            return self.capacity;
+        }
+        (-(m / k) * (1.0 - x / m).ln()).round() as usize
+    }
+
+    /// Low-level constructor that rehydrates a filter from its raw
+    /// parameters and packed bit storage, without recomputing anything
+    /// from scratch. Intended for callers (e.g. block/database storage)
+    /// that already have these values on hand, such as `from_bytes` below.
+    /// Rejects a `bits` buffer that is too short to cover the derived bit
+    /// length instead of silently truncating into a smaller `BitVec`,
+    /// which would otherwise let a later `add`/`contains` index out of
+    /// bounds and panic. Likewise rejects a `capacity`/`error_rate` that
+    /// `new()` would have panicked on, since (unlike `new()`) the values
+    /// here typically come from untrusted on-disk or network data rather
+    /// than a caller's own code — a `capacity` of zero in particular would
+    /// make `iterations` divide by zero and leave `contains`/`add`
+    /// iterating `0..usize::MAX`, a reproducible hang.
+    pub fn from_parts(
+        capacity: usize,
+        error_rate: f64,
+        dup_check: bool,
+        bits: Vec<u8>,
+        num_of_elements: usize,
+    ) -> Result<BloomFilter, &'static str> {
+        if capacity == 0 {
+            return Err("capacity must be greater than zero");
+        }
+        if error_rate <= 0.0 || error_rate > 1.0 {
+            return Err("error_rate must be greater than 0.0 and less than 1.0");
+        }
+        let num_of_bits = pow2_nbits(capacity, error_rate);
+        let required_bytes = num_of_bits.div_ceil(8);
+        if bits.len() < required_bytes {
+            return Err("bits buffer is too short for the derived filter size");
+        }
+        let num_of_hashfuncs = iterations(num_of_bits, capacity);
+        let mut bitvec = BitVec::from_bytes(&bits);
+        bitvec.truncate(num_of_bits);
+        Ok(BloomFilter {
+            bitvec,
+            mask: (num_of_bits - 1) as u64,
+            capacity,
+            error_rate,
+            num_of_hashfuncs,
+            num_of_elements,
+            dup_check,
+            journal: None,
+        })
+    }
+
+    /// Serializes the filter to a self-describing byte buffer: a header
+    /// (magic, version, capacity, error_rate, num_of_hashfuncs,
+    /// num_of_elements, dup_check, bitvec bit length) followed by the
+    /// packed bit storage, so it can be written to disk or sent over the
+    /// wire and reloaded with `from_bytes` without recomputing anything.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        out.extend_from_slice(MAGIC);
+        out.push(VERSION);
+        out.extend_from_slice(&(self.capacity as u64).to_le_bytes());
+        out.extend_from_slice(&self.error_rate.to_le_bytes());
+        out.extend_from_slice(&(self.num_of_hashfuncs as u64).to_le_bytes());
+        out.extend_from_slice(&(self.num_of_elements as u64).to_le_bytes());
+        out.push(self.dup_check as u8);
+        out.extend_from_slice(&(self.bitvec_len() as u64).to_le_bytes());
+        out.extend_from_slice(&self.bitvec.to_bytes());
+        out
+    }
+
+    /// Reconstructs a filter previously serialized with `to_bytes`.
+    /// Validates the header and that the stored `num_of_hashfuncs` and
+    /// bit length agree with what `capacity`/`error_rate` would derive
+    /// today, so a filter saved by an incompatible version is rejected
+    /// rather than silently misread.
+    pub fn from_bytes(data: &[u8]) -> Result<BloomFilter, &'static str> {
+        const HEADER_LEN: usize = 4 + 1 + 8 + 8 + 8 + 8 + 1 + 8;
+        if data.len() < HEADER_LEN {
+            return Err("buffer is too small to contain a BloomFilter header");
+        }
+        if &data[0..4] != MAGIC {
+            return Err("buffer does not start with the BloomFilter magic bytes");
+        }
+        if data[4] != VERSION {
+            return Err("buffer was serialized with an unsupported BloomFilter version");
+        }
+        let mut offset = 5;
+        let read_u64 = |offset: &mut usize| -> u64 {
+            let bytes: [u8; 8] = data[*offset..*offset + 8].try_into().unwrap();
+            *offset += 8;
+            u64::from_le_bytes(bytes)
+        };
+        let capacity = read_u64(&mut offset) as usize;
+        let error_rate = f64::from_le_bytes(data[offset..offset + 8].try_into().unwrap());
+        offset += 8;
+        let num_of_hashfuncs = read_u64(&mut offset) as usize;
+        let num_of_elements = read_u64(&mut offset) as usize;
+        let dup_check = data[offset] != 0;
+        offset += 1;
+        let bit_len = read_u64(&mut offset) as usize;
+
+        if capacity == 0 {
+            return Err("capacity must be greater than zero");
+        }
+        if error_rate <= 0.0 || error_rate > 1.0 {
+            return Err("error_rate must be greater than 0.0 and less than 1.0");
+        }
+
+        let expected_bit_len = pow2_nbits(capacity, error_rate);
+        let expected_num_of_hashfuncs = iterations(expected_bit_len, capacity);
+        if bit_len != expected_bit_len || num_of_hashfuncs != expected_num_of_hashfuncs {
+            return Err("stored parameters do not match the derived filter size");
+        }
+
+        let bits = data[offset..].to_vec();
+        BloomFilter::from_parts(capacity, error_rate, dup_check, bits, num_of_elements)
     }
 }
 
@@ -240,4 +514,208 @@ mod tests {
             assert!(b.contains(i.as_bytes()))
         }
     }
+
+    #[test]
+    fn test_false_positive_rate_stays_below_configured_bound() {
+        let error_rate = 0.01;
+        let mut b = BloomFilter::new(20000, error_rate, true);
+        for i in 0..10000 {
+            b.add(format!("inserted-{}", i).as_bytes()).unwrap();
+        }
+        let false_positives = (0..10000)
+            .filter(|i| b.contains(format!("probe-{}", i).as_bytes()))
+            .count();
+        let observed_rate = false_positives as f64 / 10000.0;
+        assert!(
+            observed_rate < error_rate * 3.0,
+            "observed false-positive rate {} is too far above the configured {}",
+            observed_rate,
+            error_rate
+        );
+    }
+
+    #[test]
+    fn test_to_bytes_from_bytes_roundtrip() {
+        let mut b = BloomFilter::new(20000, 0.01, true);
+        b.add("Test".as_bytes()).unwrap();
+        b.add("Another".as_bytes()).unwrap();
+        let bytes = b.to_bytes();
+        let restored = BloomFilter::from_bytes(&bytes).unwrap();
+        assert_eq!(restored.capacity(), b.capacity());
+        assert_eq!(restored.error_rate(), b.error_rate());
+        assert_eq!(restored.len(), b.len());
+        assert!(restored.contains("Test".as_bytes()));
+        assert!(restored.contains("Another".as_bytes()));
+        assert!(!restored.contains("Missing".as_bytes()));
+    }
+
+    #[test]
+    fn test_from_bytes_rejects_bad_magic() {
+        let mut b = BloomFilter::new(100, 0.01, true);
+        b.add("Test".as_bytes()).unwrap();
+        let mut bytes = b.to_bytes();
+        bytes[0] = b'X';
+        assert!(BloomFilter::from_bytes(&bytes).is_err());
+    }
+
+    #[test]
+    fn test_from_bytes_rejects_truncated_buffer() {
+        assert!(BloomFilter::from_bytes(&[1, 2, 3]).is_err());
+    }
+
+    #[test]
+    fn test_from_bytes_rejects_truncated_body() {
+        const HEADER_LEN: usize = 4 + 1 + 8 + 8 + 8 + 8 + 1 + 8;
+        let mut b = BloomFilter::new(20000, 0.01, true);
+        b.add("Test".as_bytes()).unwrap();
+        let bytes = b.to_bytes();
+        assert!(bytes.len() > HEADER_LEN + 1);
+        let truncated = &bytes[..HEADER_LEN + 1];
+        assert!(BloomFilter::from_bytes(truncated).is_err());
+    }
+
+    #[test]
+    fn test_from_bytes_rejects_zero_capacity() {
+        let mut b = BloomFilter::new(20000, 0.01, true);
+        b.add("Test".as_bytes()).unwrap();
+        let mut bytes = b.to_bytes();
+        // capacity is the first header field after the 4-byte magic and
+        // 1-byte version; zero it to simulate a crafted/corrupted buffer.
+        bytes[5..13].copy_from_slice(&0_u64.to_le_bytes());
+        assert!(BloomFilter::from_bytes(&bytes).is_err());
+    }
+
+    #[test]
+    fn test_from_parts_rejects_zero_capacity() {
+        assert!(BloomFilter::from_parts(0, 0.01, true, vec![0; 8], 0).is_err());
+    }
+
+    #[test]
+    fn test_from_parts_rejects_bad_error_rate() {
+        assert!(BloomFilter::from_parts(100, 0.0, true, vec![0; 8], 0).is_err());
+        assert!(BloomFilter::from_parts(100, 1.5, true, vec![0; 8], 0).is_err());
+    }
+
+    #[test]
+    fn test_add_value_and_contains_value_with_integers() {
+        let mut b = BloomFilter::new(1000, 0.01, true);
+        assert!(b.add_value(&42_u64).unwrap());
+        assert!(b.contains_value(&42_u64));
+        assert!(!b.contains_value(&7_u64));
+    }
+
+    #[test]
+    fn test_add_value_and_contains_value_with_strings() {
+        let mut b = BloomFilter::new(1000, 0.01, true);
+        let s = String::from("Test");
+        assert!(b.add_value(&s).unwrap());
+        assert!(b.contains_value(&s));
+        assert!(!b.contains_value(&String::from("Other")));
+    }
+
+    #[test]
+    fn test_add_value_with_custom_hash_type() {
+        #[derive(Hash)]
+        struct Point {
+            x: i32,
+            y: i32,
+        }
+        let mut b = BloomFilter::new(1000, 0.01, true);
+        let origin = Point { x: 0, y: 0 };
+        assert!(b.add_value(&origin).unwrap());
+        assert!(b.contains_value(&origin));
+        assert!(!b.contains_value(&Point { x: 1, y: 1 }));
+    }
+
+    #[test]
+    fn test_union() {
+        let mut a = BloomFilter::new(1000, 0.01, true);
+        let mut b = BloomFilter::new(1000, 0.01, true);
+        a.add("Shard A".as_bytes()).unwrap();
+        b.add("Shard B".as_bytes()).unwrap();
+        a.union(&b).unwrap();
+        assert!(a.contains("Shard A".as_bytes()));
+        assert!(a.contains("Shard B".as_bytes()));
+    }
+
+    #[test]
+    fn test_intersect() {
+        let mut a = BloomFilter::new(1000, 0.01, true);
+        let mut b = BloomFilter::new(1000, 0.01, true);
+        a.add("Shared".as_bytes()).unwrap();
+        a.add("Only in A".as_bytes()).unwrap();
+        b.add("Shared".as_bytes()).unwrap();
+        a.intersect(&b).unwrap();
+        assert!(a.contains("Shared".as_bytes()));
+    }
+
+    #[test]
+    fn test_intersect_lowers_len_so_add_is_not_wrongly_refused() {
+        let mut a = BloomFilter::new(10, 0.01, true);
+        let mut b = BloomFilter::new(10, 0.01, true);
+        for i in 0..10 {
+            a.add(format!("a-only-{}", i).as_bytes()).unwrap();
+        }
+        b.add("a-only-0".as_bytes()).unwrap();
+        assert_eq!(a.len(), 10);
+        a.intersect(&b).unwrap();
+        assert!(a.len() < 10);
+        assert!(a.add("room for more".as_bytes()).is_ok());
+    }
+
+    #[test]
+    fn test_union_rejects_mismatched_filters() {
+        let mut a = BloomFilter::new(1000, 0.01, true);
+        let b = BloomFilter::new(2000, 0.01, true);
+        assert!(a.union(&b).is_err());
+    }
+
+    #[test]
+    fn test_estimated_len() {
+        let mut b = BloomFilter::new(20000, 0.01, true);
+        for i in 0..1000 {
+            b.add(format!("element-{}", i).as_bytes()).unwrap();
+        }
+        let estimate = b.estimated_len();
+        let diff = (estimate as i64 - b.len() as i64).abs();
+        assert!(diff < (b.len() as i64) / 10, "estimate {} too far from actual {}", estimate, b.len());
+    }
+
+    #[test]
+    fn test_journal_disabled_by_default() {
+        let mut b = BloomFilter::new(1000, 0.01, true);
+        b.add("Test".as_bytes()).unwrap();
+        assert!(b.drain_set_indices().is_empty());
+    }
+
+    #[test]
+    fn test_drain_set_indices_tracks_new_bits_only() {
+        let mut b = BloomFilter::new(1000, 0.01, true);
+        b.enable_journal();
+        b.add("Test".as_bytes()).unwrap();
+        let first_drain = b.drain_set_indices();
+        assert!(!first_drain.is_empty());
+        for index in &first_drain {
+            assert_eq!(b.bitvec.get(*index), Some(true));
+        }
+        assert!(b.drain_set_indices().is_empty());
+
+        b.add("Test".as_bytes()).unwrap();
+        assert!(b.drain_set_indices().is_empty());
+    }
+
+    #[test]
+    fn test_union_is_recorded_in_the_journal() {
+        let mut a = BloomFilter::new(1000, 0.01, true);
+        let mut shard = BloomFilter::new(1000, 0.01, true);
+        a.enable_journal();
+        a.drain_set_indices();
+        shard.add("New in shard".as_bytes()).unwrap();
+        a.union(&shard).unwrap();
+        let drained = a.drain_set_indices();
+        assert!(!drained.is_empty());
+        for index in &drained {
+            assert_eq!(a.bitvec.get(*index), Some(true));
+        }
+    }
 }